@@ -1,306 +1,916 @@
-#[global_allocator]
-static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
-
-use std::env;
-use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
-use std::process::{Command, Stdio};
-use std::sync::Arc;
-use std::collections::HashMap;
-use std::sync::Mutex;
-use std::thread;
-use std::time::Duration;
-
-/// Add these imports for native ashmaize
-use hex;
-use anyhow::{Result, anyhow};
-
-// If using local crate name; adjust if the crate name differs in ce-ashmaize.
-#[cfg(feature = "native_ashmaize")]
-use ashmaize::{Rom, RomGenerationType, hash};
-
-// Simple in-memory ROM cache keyed by rom_init hex string. Feature-gated.
-#[cfg(feature = "native_ashmaize")]
-fn rom_cache() -> &'static Mutex<HashMap<String, std::sync::Arc<Rom>>> {
-    use std::sync::OnceLock;
-    static CACHE: OnceLock<Mutex<HashMap<String, std::sync::Arc<Rom>>>> = OnceLock::new();
-    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
-}
-
-fn handle_client(mut stream: TcpStream, mode: Arc<DaemonMode>) {
-    let peer = stream.peer_addr().ok();
-    let r = stream.try_clone();
-    if r.is_err() {
-        eprintln!("Failed clone stream");
-        return;
-    }
-    let mut reader = BufReader::new(stream.try_clone().unwrap());
-
-    loop {
-        let mut line = String::new();
-        match reader.read_line(&mut line) {
-            Ok(0) => {
-                // EOF
-                break;
-            }
-            Ok(_) => {
-                let pre = line.trim_end_matches(&['\r','\n'][..]).to_string();
-                if pre.len() == 0 {
-                    // ignore empty
-                    continue;
-                }
-
-                let hash_hex = match &*mode {
-                    DaemonMode::Demo => {
-                        // demo hasher: sha256(pre) + sha512(...) -> hex
-                        demo_hash_hex(pre.as_bytes())
-                    }
-                    DaemonMode::External { bin } => {
-                        // call external binary with preimage as arg
-                        match call_external_hash(bin, &pre) {
-                            Ok(h) => h,
-                            Err(e) => {
-                                eprintln!("External hash failed: {:?}", e);
-                                "err".to_string()
-                            }
-                        }
-                    }
-            DaemonMode::Native { rom_init } => {
-                // Native: allow client to optionally prefix the preimage with
-                // a rom hex and '|' separator: "<rom_hex>|<preimage>". If the
-                // prefix is present we'll use that rom init for this hash.
-                let (maybe_rom, actual_pre) = if let Some(pos) = pre.find('|') {
-                    let (r, p) = pre.split_at(pos);
-                    // skip the '|' char for p
-                    (Some(r.trim()), p[1..].trim())
-                } else {
-                    (rom_init.as_deref(), pre.as_str())
-                };
-
-                // 👇 Log ROM prefix info
-// Thêm biến static để đảm bảo chỉ in 1 lần
-                use std::sync::Once;
-                static PRINTED_ROM: Once = Once::new();
-
-                if let Some(rhex) = maybe_rom {
-                    if !rhex.is_empty() {
-                        PRINTED_ROM.call_once(|| {
-                            println!(
-                                "[client {:?}] received ROM prefix (printed once):\n\
-                                ────────────────────────────────────────────────\n\
-                                len = {}\n\
-                                first 64 chars = {}\n\
-                                ────────────────────────────────────────────────",
-                                peer,
-                                rhex.len(),
-                                &rhex[..rhex.len().min(64)]
-                            );
-                        });
-                    }
-                }
-
-
-                        // Compute
-                        match native_hash_hex(actual_pre, maybe_rom) {
-                            Ok(h) => h,
-                            Err(e) => {
-                                eprintln!("Native hash failed: {:?}", e);
-                                "err".to_string()
-                            }
-                        }
-                    }
-                };
-
-                if let Err(e) = writeln!(stream, "{}", hash_hex) {
-                    eprintln!("Failed write to client {:?}: {:?}", peer, e);
-                    break;
-                }
-                if let Err(e) = stream.flush() {
-                    eprintln!("Flush error: {:?}", e);
-                    break;
-                }
-            }
-            Err(e) => {
-                eprintln!("Read error from client {:?}: {:?}", peer, e);
-                break;
-            }
-        }
-    }
-}
-
-fn demo_hash_hex(pre: &[u8]) -> String {
-    use sha2::{Digest, Sha256, Sha512};
-    let mut d1 = Sha256::new();
-    d1.update(pre);
-    let d1b = d1.finalize();
-
-    let mut d2 = Sha512::new();
-    d2.update(&d1b);
-    d2.update(pre);
-    let out = d2.finalize();
-
-    hex::encode(out)
-}
-
-fn call_external_hash(bin: &str, pre: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let out = Command::new(bin)
-        .arg(pre)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .spawn()?
-        .wait_with_output()?;
-    let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
-    Ok(s)
-}
-
-enum DaemonMode {
-    Demo,
-    External { bin: String },
-    /// Native holds optional rom init hex string (no_pre_mine).
-    Native { rom_init: Option<String> },
-}
-
-fn main() -> anyhow::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let mut mode = DaemonMode::Demo;
-    let mut port = 4002u16;
-
-    let mut i = 1;
-    while i < args.len() {
-        match args[i].as_str() {
-            "--mode" => {
-                i += 1;
-                if i >= args.len() { break; }
-                match args[i].as_str() {
-                    "demo" => mode = DaemonMode::Demo,
-                    "external" => mode = DaemonMode::External { bin: "ashmaize-cli".to_string() },
-                    "native" => mode = DaemonMode::Native { rom_init: None },
-                    _ => {}
-                }
-            }
-            "--bin" => {
-                i += 1;
-                if i >= args.len() { break; }
-                let b = args[i].clone();
-                mode = DaemonMode::External { bin: b };
-            }
-            "--port" => {
-                i += 1;
-                if i >= args.len() { break; }
-                port = args[i].parse().unwrap_or(4000);
-            }
-            "--rom" => {
-                // allow passing no_pre_mine hex directly to daemon for native init
-                i += 1;
-                if i >= args.len() { break; }
-                let hexs = args[i].clone();
-                mode = match mode {
-                    DaemonMode::Native { .. } => DaemonMode::Native { rom_init: Some(hexs) },
-                    _ => DaemonMode::Native { rom_init: Some(hexs) },
-                };
-            }
-            _ => {}
-        }
-        i += 1;
-    }
-
-    println!("Starting ashdaemon on 127.0.0.1:{} mode={}", port,
-        match &mode {
-            DaemonMode::Demo => "demo",
-            DaemonMode::External{..} => "external",
-            DaemonMode::Native { .. } => "native",
-        });
-
-    // If native mode with rom init provided, try to validate the ROM init hex once (optional)
-    if let DaemonMode::Native { rom_init } = &mode {
-        if let Some(hexs) = rom_init {
-            // Validate that provided --rom is valid hex; fail fast if it's not.
-            match hex::decode(hexs) {
-                Ok(_) => println!("Native mode: preloading ROM init (len {})", hexs.len()),
-                Err(e) => {
-                    eprintln!("Invalid --rom hex provided: {}", e);
-                    return Err(anyhow!("Invalid --rom hex: {}", e));
-                }
-            }
-            // We do not keep the AshMaize instance global here because the library
-            // may require thread-local state; instead we will create/initialize per-hash
-            // or implement a global instance if library API supports it.
-        }
-    }
-
-    let mode_arc = Arc::new(mode);
-    let listener = TcpListener::bind(("127.0.0.1", port))?;
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(s) => {
-                let mode_c = mode_arc.clone();
-                thread::spawn(move || handle_client(s, mode_c));
-            }
-            Err(e) => {
-                eprintln!("Listener error: {:?}", e);
-                thread::sleep(Duration::from_millis(100));
-            }
-        }
-    }
-    Ok(())
-}
-
-/// Compute AshMaize hash hex using ce-ashmaize crate (native implementation).
-/// 'rom_init_hex' is optional hex string (no_pre_mine) required by algorithm init.
-/// Return lowercase hex string of hash bytes.
-fn native_hash_hex(pre: &str, rom_init_hex: Option<&str>) -> Result<String> {
-    let pre_bytes = pre.as_bytes();
-
-    #[cfg(feature = "native_ashmaize")]
-    {
-        let key = rom_init_hex.unwrap_or("default").to_string();
-
-        let rom_arc = {
-            let cache = rom_cache();
-            let mut m = cache.lock().unwrap();
-
-            if let Some(r) = m.get(&key) {
-                r.clone()
-            } else {
-                    let seed = if let Some(s) = rom_init_hex {
-            // Scavenger gửi raw bytes → lấy nguyên bytes
-            let bytes = s.as_bytes().to_vec();
-            println!(
-                "[native_hash_hex] Using RAW ROM init ({} bytes)",
-                bytes.len()
-            );
-            bytes
-        } else {
-            b"default_seed".to_vec()
-        };
-
-
-                // init ROM
-                let rom = Rom::new(
-                    &seed,
-                    RomGenerationType::TwoStep {
-                        pre_size: 16 * 1024 * 1024, // 16MB
-                        mixing_numbers: 4,
-                    },
-                    1024 * 1024 * 1024, // 1GB
-                );
-
-                let arc = std::sync::Arc::new(rom);
-                m.insert(key.clone(), arc.clone());
-                arc
-            }
-        };
-
-        let hash_bytes = hash(pre_bytes, &rom_arc, 8, 256);
-        return Ok(hex::encode(hash_bytes));
-    }
-
-    #[cfg(not(feature = "native_ashmaize"))]
-    {
-        anyhow::bail!(
-            "Native AshMaize not enabled. Compile with --features native_ashmaize"
-        );
-    }
-}
-
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+use std::env;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Add these imports for native ashmaize
+use hex;
+use anyhow::{Result, anyhow};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+mod transport;
+use transport::SecureStream;
+
+mod signing;
+use signing::NamedSigningKey;
+
+mod protocol;
+
+mod pool;
+
+mod worker_pool;
+use worker_pool::WorkerPool;
+
+#[cfg(feature = "native_ashmaize")]
+mod rom_cache;
+
+/// Default `DaemonMode::Pool` target difficulty when `--difficulty` isn't given.
+const DEFAULT_DIFFICULTY_BITS: u32 = 20;
+/// How often `DaemonMode::Pool` mints a fresh job for connected clients.
+const POOL_JOB_ROTATE_INTERVAL: Duration = Duration::from_secs(30);
+
+// If using local crate name; adjust if the crate name differs in ce-ashmaize.
+#[cfg(feature = "native_ashmaize")]
+use ashmaize::{Rom, RomGenerationType, hash};
+
+/// Either a freshly generated ROM or one reconstructed from an mmap'd
+/// `--rom-dir` cache file. Kept behind an `Arc` either way so concurrent
+/// hash workers share one underlying allocation/mapping.
+#[cfg(feature = "native_ashmaize")]
+enum CachedRom {
+    Owned(Rom),
+    Mapped(memmap2::Mmap),
+}
+
+#[cfg(feature = "native_ashmaize")]
+impl CachedRom {
+    fn hash(&self, pre: &[u8], rounds: usize, out_len: usize) -> Vec<u8> {
+        match self {
+            CachedRom::Owned(rom) => hash(pre, rom, rounds, out_len),
+            // The ROM's on-disk body is exactly its in-memory byte layout,
+            // so the crate can build a (borrowed, zero-copy) `Rom` view
+            // straight over the mapping instead of re-running generation.
+            //
+            // `Rom::from_bytes`/`Rom::as_bytes` (used on the write side in
+            // `generate_and_persist_rom`) are assumed to exist and to
+            // round-trip that layout exactly; this vendoring of `ashmaize`
+            // doesn't ship with this tree, so that assumption could not be
+            // checked against the real crate here. Verify both methods
+            // against the actual `ashmaize` source before enabling
+            // `--rom-dir` in production -- a mismatch here would only show
+            // up as a wrong digest at `--features native_ashmaize` runtime,
+            // not as a compile error.
+            CachedRom::Mapped(mmap) => {
+                let rom = Rom::from_bytes(rom_cache::body(mmap));
+                hash(pre, &rom, rounds, out_len)
+            }
+        }
+    }
+}
+
+// In-memory ROM cache keyed by the hex encoding of the ROM seed bytes
+// ("default" when there is none), holding either an owned ROM we generated
+// this process or a disk-backed mapping from `--rom-dir`.
+// Feature-gated.
+#[cfg(feature = "native_ashmaize")]
+fn rom_cache() -> &'static Mutex<HashMap<String, std::sync::Arc<CachedRom>>> {
+    use std::sync::OnceLock;
+    static CACHE: OnceLock<Mutex<HashMap<String, std::sync::Arc<CachedRom>>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Directory set via `--rom-dir`, if any. Read by [`native_hash_bytes`] on
+/// every in-memory cache miss to decide whether to check/populate the disk
+/// cache before paying for a fresh `Rom::new` generation.
+#[cfg(feature = "native_ashmaize")]
+fn rom_dir_cell() -> &'static std::sync::OnceLock<Option<std::path::PathBuf>> {
+    static ROM_DIR: std::sync::OnceLock<Option<std::path::PathBuf>> = std::sync::OnceLock::new();
+    &ROM_DIR
+}
+
+/// Set the `--rom-dir` path. Must be called at most once, before the first
+/// hash request is served; subsequent calls are ignored.
+#[cfg(feature = "native_ashmaize")]
+fn set_rom_dir(dir: Option<std::path::PathBuf>) {
+    let _ = rom_dir_cell().set(dir);
+}
+
+#[cfg(feature = "native_ashmaize")]
+fn rom_dir() -> Option<&'static std::path::Path> {
+    rom_dir_cell().get().and_then(|d| d.as_deref())
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    mode: Arc<DaemonMode>,
+    signer: Arc<Option<NamedSigningKey>>,
+    worker: WorkerPool,
+) {
+    let peer = stream.peer_addr().ok();
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line).await {
+            Ok(0) => {
+                // EOF
+                break;
+            }
+            Ok(_) => {
+                let pre = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+                if pre.is_empty() {
+                    // ignore empty
+                    continue;
+                }
+
+                let hash_hex = compute_hash_response(&mode, &pre, peer, &signer, &worker).await;
+
+                if let Err(e) = write_half.write_all(format!("{}\n", hash_hex).as_bytes()).await {
+                    eprintln!("Failed write to client {:?}: {:?}", peer, e);
+                    break;
+                }
+                if let Err(e) = write_half.flush().await {
+                    eprintln!("Flush error: {:?}", e);
+                    break;
+                }
+            }
+            Err(e) => {
+                eprintln!("Read error from client {:?}: {:?}", peer, e);
+                break;
+            }
+        }
+    }
+}
+
+/// Same request/response loop as [`handle_client`], but running over a
+/// [`SecureStream`] established via [`transport::server_handshake`] instead
+/// of a plaintext `TcpStream`.
+async fn handle_secure_client(
+    mut secure: SecureStream,
+    mode: Arc<DaemonMode>,
+    signer: Arc<Option<NamedSigningKey>>,
+    worker: WorkerPool,
+) {
+    let peer = secure.peer_addr().ok();
+
+    loop {
+        match secure.read_line().await {
+            Ok(None) => break,
+            Ok(Some(line)) => {
+                let pre = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+                if pre.is_empty() {
+                    continue;
+                }
+
+                let hash_hex = compute_hash_response(&mode, &pre, peer, &signer, &worker).await;
+
+                if let Err(e) = secure.write_line(&hash_hex).await {
+                    eprintln!("Failed secure write to client {:?}: {:?}", peer, e);
+                    break;
+                }
+            }
+            Err(e) => {
+                eprintln!("Secure read error from client {:?}: {:?}", peer, e);
+                break;
+            }
+        }
+    }
+}
+
+/// Dispatch a single preimage line to the configured [`DaemonMode`], sign it
+/// if a signing key was configured, and return the full response line.
+/// Shared by both the plaintext and the `--secure` connection handlers.
+async fn compute_hash_response(
+    mode: &DaemonMode,
+    pre: &str,
+    peer: Option<std::net::SocketAddr>,
+    signer: &Option<NamedSigningKey>,
+    worker: &WorkerPool,
+) -> String {
+    let hash_hex = compute_hash_hex(mode, pre, peer, worker).await;
+    // A failed hash was never actually computed, so there's nothing for a
+    // signature to attest to -- don't sign "err" and don't append a
+    // `<keyname>:<sig>` suffix that would make a failure look like a
+    // verifiable response. Mirrors how compute_binary_response leaves
+    // signature: None on error.
+    match signer {
+        Some(key) if hash_hex != "err" => {
+            let hash_bytes = hex::decode(&hash_hex).unwrap_or_default();
+            format!("{} {}", hash_hex, key.sign_response(pre.as_bytes(), &hash_bytes))
+        }
+        _ => hash_hex,
+    }
+}
+
+/// Compute just the hex-encoded digest for `pre` under `mode`.
+async fn compute_hash_hex(
+    mode: &DaemonMode,
+    pre: &str,
+    peer: Option<std::net::SocketAddr>,
+    worker: &WorkerPool,
+) -> String {
+    match mode {
+        DaemonMode::Demo => {
+            // demo hasher: sha256(pre) + sha512(...) -> hex
+            demo_hash_hex(pre.as_bytes())
+        }
+        DaemonMode::External { bin } => {
+            // call external binary with preimage as arg
+            match call_external_hash(bin, pre) {
+                Ok(h) => h,
+                Err(e) => {
+                    eprintln!("External hash failed: {:?}", e);
+                    "err".to_string()
+                }
+            }
+        }
+        DaemonMode::Native { rom_init } => {
+            // Native: allow client to optionally prefix the preimage with
+            // a rom hex and '|' separator: "<rom_hex>|<preimage>". If the
+            // prefix is present we'll use that rom init for this hash.
+            let (maybe_rom, actual_pre) = if let Some(pos) = pre.find('|') {
+                let (r, p) = pre.split_at(pos);
+                // skip the '|' char for p
+                (Some(r.trim().to_string()), p[1..].trim().to_string())
+            } else {
+                (rom_init.clone(), pre.to_string())
+            };
+
+            // Log the first ROM prefix we see, once, so debugging a
+            // misbehaving client doesn't spam the log on every request.
+            use std::sync::Once;
+            static PRINTED_ROM: Once = Once::new();
+
+            if let Some(rhex) = &maybe_rom {
+                if !rhex.is_empty() {
+                    PRINTED_ROM.call_once(|| {
+                        println!(
+                            "[client {:?}] received ROM prefix (printed once):\n\
+                            ────────────────────────────────────────────────\n\
+                            len = {}\n\
+                            first 64 chars = {}\n\
+                            ────────────────────────────────────────────────",
+                            peer,
+                            rhex.len(),
+                            rhex.chars().take(64).collect::<String>()
+                        );
+                    });
+                }
+            }
+
+            // Compute
+            match worker.run(move || native_hash_hex(&actual_pre, maybe_rom.as_deref())).await {
+                Ok(Ok(h)) => h,
+                Ok(Err(e)) => {
+                    eprintln!("Native hash failed: {:?}", e);
+                    "err".to_string()
+                }
+                Err(e) => {
+                    eprintln!("Native hash worker failed: {:?}", e);
+                    "err".to_string()
+                }
+            }
+        }
+        DaemonMode::Pool { .. } => {
+            eprintln!("Client {:?} used the line protocol against a pool daemon", peer);
+            "err".to_string()
+        }
+    }
+}
+
+/// Binary-protocol connection loop: one length-prefixed [`protocol::Request`]
+/// in, one length-prefixed [`protocol::Response`] out. This is the default
+/// wire format; `--legacy-line` falls back to [`handle_client`].
+async fn handle_client_binary(
+    mut stream: TcpStream,
+    mode: Arc<DaemonMode>,
+    signer: Arc<Option<NamedSigningKey>>,
+    worker: WorkerPool,
+) {
+    let peer = stream.peer_addr().ok();
+
+    loop {
+        match protocol::read_request(&mut stream).await {
+            Ok(None) => break,
+            Ok(Some(req)) => {
+                let resp = compute_binary_response(&mode, req, &signer, &worker).await;
+                if let Err(e) = protocol::write_response(&mut stream, &resp).await {
+                    eprintln!("Failed to write response to {:?}: {:?}", peer, e);
+                    break;
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to read request from {:?}: {:?}", peer, e);
+                break;
+            }
+        }
+    }
+}
+
+/// Same as [`handle_client_binary`] but running over a `--secure`
+/// [`SecureStream`] instead of a plaintext `TcpStream`.
+async fn handle_secure_client_binary(
+    mut secure: SecureStream,
+    mode: Arc<DaemonMode>,
+    signer: Arc<Option<NamedSigningKey>>,
+    worker: WorkerPool,
+) {
+    let peer = secure.peer_addr().ok();
+
+    loop {
+        match secure.read_frame().await {
+            Ok(None) => break,
+            Ok(Some(body)) => {
+                let req = match protocol::decode_request(&body) {
+                    Ok(req) => req,
+                    Err(e) => {
+                        eprintln!("Failed to decode request from {:?}: {:?}", peer, e);
+                        break;
+                    }
+                };
+                let resp = compute_binary_response(&mode, req, &signer, &worker).await;
+                let encoded = match protocol::encode_response(&resp) {
+                    Ok(b) => b,
+                    Err(e) => {
+                        eprintln!("Failed to encode response for {:?}: {:?}", peer, e);
+                        break;
+                    }
+                };
+                if let Err(e) = secure.write_frame(&encoded).await {
+                    eprintln!("Failed secure write to client {:?}: {:?}", peer, e);
+                    break;
+                }
+            }
+            Err(e) => {
+                eprintln!("Secure read error from client {:?}: {:?}", peer, e);
+                break;
+            }
+        }
+    }
+}
+
+/// Dispatch a decoded [`protocol::Request`] to the configured [`DaemonMode`],
+/// taking `rounds`/`out_len` from the request instead of the old hard-coded
+/// `8, 256`. The heavy `Native` path runs on `worker` so one connection's
+/// hash can't starve the others. Signs the response the same way
+/// [`compute_hash_response`] does for the line protocol, if `signer` holds a
+/// key.
+async fn compute_binary_response(
+    mode: &DaemonMode,
+    req: protocol::Request,
+    signer: &Option<NamedSigningKey>,
+    worker: &WorkerPool,
+) -> protocol::Response {
+    if let Err(e) = protocol::validate_request_params(&req) {
+        return protocol::Response { hash: Vec::new(), error: Some(e.to_string()), signature: None };
+    }
+
+    let preimage = req.preimage.clone();
+    let result: Result<Vec<u8>> = match mode {
+        DaemonMode::Demo => Ok(demo_hash_bytes(&req.preimage)),
+        DaemonMode::External { bin } => {
+            call_external_hash_bytes(bin, &req.preimage).map_err(|e| anyhow!("external hash failed: {e}"))
+        }
+        DaemonMode::Native { rom_init } => {
+            let rom_bytes = req
+                .rom_init
+                .clone()
+                .or_else(|| rom_init.as_ref().map(|s| s.as_bytes().to_vec()));
+            worker
+                .run(move || native_hash_bytes(&req.preimage, rom_bytes.as_deref(), req.rounds, req.out_len))
+                .await
+                .unwrap_or_else(|e| Err(anyhow!("hash worker failed: {e}")))
+        }
+        DaemonMode::Pool { .. } => Err(anyhow!(
+            "pool mode does not serve the hash request/response protocol; use the job/share protocol instead"
+        )),
+    };
+
+    match result {
+        Ok(hash) => {
+            let signature = signer.as_ref().map(|key| key.sign_response(&preimage, &hash));
+            protocol::Response { hash, error: None, signature }
+        }
+        Err(e) => protocol::Response { hash: Vec::new(), error: Some(e.to_string()), signature: None },
+    }
+}
+
+fn demo_hash_bytes(pre: &[u8]) -> Vec<u8> {
+    use sha2::{Digest, Sha256, Sha512};
+    let mut d1 = Sha256::new();
+    d1.update(pre);
+    let d1b = d1.finalize();
+
+    let mut d2 = Sha512::new();
+    d2.update(&d1b);
+    d2.update(pre);
+    d2.finalize().to_vec()
+}
+
+fn demo_hash_hex(pre: &[u8]) -> String {
+    hex::encode(demo_hash_bytes(pre))
+}
+
+fn call_external_hash(bin: &str, pre: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let out = Command::new(bin)
+        .arg(pre)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?
+        .wait_with_output()?;
+    let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    Ok(s)
+}
+
+/// Byte-oriented variant used by the binary protocol, where the preimage
+/// may not be valid UTF-8. The external binary's stdout is still assumed to
+/// be a hex digest; if it isn't, the raw trimmed bytes are passed through.
+fn call_external_hash_bytes(bin: &str, pre: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    let arg: std::ffi::OsString = {
+        use std::os::unix::ffi::OsStrExt;
+        std::ffi::OsStr::from_bytes(pre).to_os_string()
+    };
+    #[cfg(not(unix))]
+    let arg: std::ffi::OsString = String::from_utf8_lossy(pre).into_owned().into();
+
+    let out = Command::new(bin)
+        .arg(arg)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?
+        .wait_with_output()?;
+    let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    Ok(hex::decode(&s).unwrap_or_else(|_| s.into_bytes()))
+}
+
+enum DaemonMode {
+    Demo,
+    External { bin: String },
+    /// Native holds optional rom init hex string (no_pre_mine).
+    Native { rom_init: Option<String> },
+    /// Stratum-style mining coordinator: hands out jobs via `pool::JobBoard`
+    /// and validates submitted shares against `target`.
+    Pool { rom_init: Option<String>, board: Arc<pool::JobBoard>, target: [u8; 32] },
+}
+
+/// `ashdaemon verify <preimage> <response_line> <name:base64key>...`
+///
+/// Standalone helper so a client can check that a response line actually
+/// came from a daemon holding one of the listed keys, without needing the
+/// rest of the daemon's dependencies.
+fn run_verify_cli(args: &[String]) -> anyhow::Result<()> {
+    if args.len() < 3 {
+        anyhow::bail!("usage: ashdaemon verify <preimage> <response_line> <name:base64key>...");
+    }
+    let preimage = args[0].as_bytes();
+    let response_line = &args[1];
+    let keyring = signing::Keyring::parse(&args[2..])?;
+
+    match keyring.verify_response(preimage, response_line) {
+        Ok(()) => {
+            println!("ACCEPT");
+            Ok(())
+        }
+        Err(e) => {
+            println!("REJECT: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `ashdaemon ping <host:port> <preimage> [--secure] [--legacy-line]`
+///
+/// Minimal client: connects to a running daemon, optionally runs the
+/// `--secure` handshake, sends one preimage, and prints the response.
+/// Speaks the length-prefixed binary protocol by default to match the
+/// daemon's own default; pass `--legacy-line` if the daemon was started
+/// with that flag. Mainly useful for smoke-testing a daemon from the
+/// command line without a separate client binary.
+async fn run_ping_cli(args: &[String]) -> anyhow::Result<()> {
+    if args.len() < 2 {
+        anyhow::bail!("usage: ashdaemon ping <host:port> <preimage> [--secure] [--legacy-line]");
+    }
+    let addr = &args[0];
+    let preimage = &args[1];
+    let secure = args[2..].iter().any(|a| a == "--secure");
+    let legacy_line = args[2..].iter().any(|a| a == "--legacy-line");
+
+    let stream = TcpStream::connect(addr).await?;
+    if legacy_line {
+        if secure {
+            let mut secure = transport::client_handshake(stream).await?;
+            secure.write_line(preimage).await?;
+            match secure.read_line().await? {
+                Some(line) => println!("{line}"),
+                None => anyhow::bail!("daemon closed the connection without a response"),
+            }
+        } else {
+            let (read_half, mut write_half) = stream.into_split();
+            let mut reader = BufReader::new(read_half);
+            write_half.write_all(format!("{preimage}\n").as_bytes()).await?;
+            write_half.flush().await?;
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 {
+                anyhow::bail!("daemon closed the connection without a response");
+            }
+            println!("{}", line.trim_end_matches(&['\r', '\n'][..]));
+        }
+    } else {
+        let req = protocol::Request { rom_init: None, preimage: preimage.as_bytes().to_vec(), out_len: 256, rounds: 8 };
+        let body = protocol::encode_request(&req)?;
+        let resp = if secure {
+            let mut secure = transport::client_handshake(stream).await?;
+            secure.write_frame(&body).await?;
+            match secure.read_frame().await? {
+                Some(body) => protocol::decode_response(&body)?,
+                None => anyhow::bail!("daemon closed the connection without a response"),
+            }
+        } else {
+            let mut stream = stream;
+            protocol::write_frame(&mut stream, &body).await?;
+            match protocol::read_frame(&mut stream).await? {
+                Some(body) => protocol::decode_response(&body)?,
+                None => anyhow::bail!("daemon closed the connection without a response"),
+            }
+        };
+        print_ping_response(&resp);
+    }
+    Ok(())
+}
+
+/// Render a binary-protocol [`protocol::Response`] the way the legacy
+/// line protocol prints its response line (`<hash_hex>`, plus
+/// ` <keyname>:<sig>` when signed), so a successful `ping`'s output looks
+/// the same regardless of which wire format it used. An error prints as
+/// `err: <message>` rather than the line protocol's bare `err`.
+fn print_ping_response(resp: &protocol::Response) {
+    match &resp.error {
+        Some(e) => println!("err: {e}"),
+        None => match &resp.signature {
+            Some(sig) => println!("{} {}", hex::encode(&resp.hash), sig),
+            None => println!("{}", hex::encode(&resp.hash)),
+        },
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "verify" {
+        return run_verify_cli(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "ping" {
+        return run_ping_cli(&args[2..]).await;
+    }
+
+    let mut mode = DaemonMode::Demo;
+    let mut port = 4002u16;
+    let mut bind_addr = "127.0.0.1".to_string();
+    let mut secure = false;
+    let mut sign_key_path: Option<std::path::PathBuf> = None;
+    let mut legacy_line = false;
+    let mut workers = worker_pool::default_workers();
+    let mut rom_dir_path: Option<std::path::PathBuf> = None;
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--mode" => {
+                i += 1;
+                if i >= args.len() { break; }
+                match args[i].as_str() {
+                    "demo" => mode = DaemonMode::Demo,
+                    "external" => mode = DaemonMode::External { bin: "ashmaize-cli".to_string() },
+                    "native" => mode = DaemonMode::Native { rom_init: None },
+                    "pool" => {
+                        // Preserve a target/board set by an earlier
+                        // --difficulty, the same way --rom preserves
+                        // rom_init, so flag order doesn't matter.
+                        mode = match mode {
+                            DaemonMode::Pool { rom_init, board, target } => {
+                                DaemonMode::Pool { rom_init, board, target }
+                            }
+                            _ => DaemonMode::Pool {
+                                rom_init: None,
+                                board: Arc::new(pool::JobBoard::new()),
+                                target: pool::mode_pool_target(None, DEFAULT_DIFFICULTY_BITS),
+                            },
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            "--bin" => {
+                i += 1;
+                if i >= args.len() { break; }
+                let b = args[i].clone();
+                mode = DaemonMode::External { bin: b };
+            }
+            "--port" => {
+                i += 1;
+                if i >= args.len() { break; }
+                port = args[i].parse().unwrap_or(4000);
+            }
+            "--bind" => {
+                // interface to listen on; defaults to 127.0.0.1 so the
+                // daemon stays loopback-only unless explicitly opened up
+                // (e.g. "0.0.0.0" once --secure is in use).
+                i += 1;
+                if i >= args.len() { break; }
+                bind_addr = args[i].clone();
+            }
+            "--workers" => {
+                i += 1;
+                if i >= args.len() { break; }
+                workers = args[i].parse().unwrap_or(workers);
+            }
+            "--rom-dir" => {
+                // persist/reuse generated ROMs across restarts; see rom_cache.rs
+                i += 1;
+                if i >= args.len() { break; }
+                rom_dir_path = Some(std::path::PathBuf::from(&args[i]));
+            }
+            "--rom" => {
+                // allow passing no_pre_mine hex directly to daemon for native/pool init
+                i += 1;
+                if i >= args.len() { break; }
+                let hexs = args[i].clone();
+                mode = match mode {
+                    DaemonMode::Native { .. } => DaemonMode::Native { rom_init: Some(hexs) },
+                    DaemonMode::Pool { board, target, .. } => {
+                        DaemonMode::Pool { rom_init: Some(hexs), board, target }
+                    }
+                    _ => DaemonMode::Native { rom_init: Some(hexs) },
+                };
+            }
+            "--difficulty" => {
+                // leading-zero-bit target difficulty for DaemonMode::Pool
+                i += 1;
+                if i >= args.len() { break; }
+                let bits: u32 = args[i].parse().unwrap_or(DEFAULT_DIFFICULTY_BITS);
+                let target = pool::target_from_leading_zero_bits(bits);
+                mode = match mode {
+                    DaemonMode::Pool { rom_init, board, .. } => DaemonMode::Pool { rom_init, board, target },
+                    _ => DaemonMode::Pool { rom_init: None, board: Arc::new(pool::JobBoard::new()), target },
+                };
+            }
+            "--secure" => {
+                secure = true;
+            }
+            "--sign-key" => {
+                i += 1;
+                if i >= args.len() { break; }
+                sign_key_path = Some(std::path::PathBuf::from(&args[i]));
+            }
+            "--legacy-line" => {
+                legacy_line = true;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    println!("Starting ashdaemon on {}:{} mode={} secure={} protocol={} workers={}", bind_addr, port,
+        match &mode {
+            DaemonMode::Demo => "demo",
+            DaemonMode::External{..} => "external",
+            DaemonMode::Native { .. } => "native",
+            DaemonMode::Pool { .. } => "pool",
+        },
+        secure,
+        if legacy_line { "legacy-line" } else { "binary" },
+        workers);
+
+    // If native/pool mode with rom init provided, try to validate the ROM init hex once (optional)
+    let rom_init_to_validate = match &mode {
+        DaemonMode::Native { rom_init } => rom_init.as_ref(),
+        DaemonMode::Pool { rom_init, .. } => rom_init.as_ref(),
+        _ => None,
+    };
+    if let Some(hexs) = rom_init_to_validate {
+        // Validate that provided --rom is valid hex; fail fast if it's not.
+        match hex::decode(hexs) {
+            Ok(_) => println!("Preloading ROM init (len {})", hexs.len()),
+            Err(e) => {
+                eprintln!("Invalid --rom hex provided: {}", e);
+                return Err(anyhow!("Invalid --rom hex: {}", e));
+            }
+        }
+        // We do not keep the AshMaize instance global here because the library
+        // may require thread-local state; instead we will create/initialize per-hash
+        // or implement a global instance if library API supports it.
+    }
+
+    if let Some(dir) = &rom_dir_path {
+        #[cfg(feature = "native_ashmaize")]
+        {
+            println!("ROM disk cache: {}", dir.display());
+            set_rom_dir(Some(dir.clone()));
+        }
+        #[cfg(not(feature = "native_ashmaize"))]
+        {
+            eprintln!("--rom-dir {} has no effect without --features native_ashmaize", dir.display());
+        }
+    }
+
+    let worker = WorkerPool::new(workers);
+
+    if let DaemonMode::Pool { board, target, rom_init } = &mode {
+        println!("Pool difficulty: {:.2} (target {})", pool::difficulty_ratio(target), hex::encode(target));
+        let board = board.clone();
+        let target = *target;
+        let rom_bytes = rom_init.as_ref().map(|s| s.as_bytes().to_vec());
+        board.rotate(rom_bytes.clone(), target);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(POOL_JOB_ROTATE_INTERVAL).await;
+                let job = board.rotate(rom_bytes.clone(), target);
+                let (accepted, rejected) = board.counts();
+                println!("Pool job {} (accepted={accepted} rejected={rejected})", job.job_id);
+            }
+        });
+    }
+
+    let signer: Arc<Option<NamedSigningKey>> = match &sign_key_path {
+        Some(p) => {
+            let key = NamedSigningKey::load_or_generate(p)?;
+            println!("Signing key: {}", key.public_key_line());
+            Arc::new(Some(key))
+        }
+        None => Arc::new(None),
+    };
+
+    let mode_arc = Arc::new(mode);
+    let listener = TcpListener::bind((bind_addr.as_str(), port)).await?;
+
+    loop {
+        match listener.accept().await {
+            Ok((s, _addr)) => {
+                if let DaemonMode::Pool { board, .. } = &*mode_arc {
+                    let board = board.clone();
+                    let worker_c = worker.clone();
+                    if secure {
+                        tokio::spawn(async move {
+                            match transport::server_handshake(s).await {
+                                Ok(secure_stream) => pool::handle_secure_pool_client(secure_stream, board, worker_c).await,
+                                Err(e) => eprintln!("Secure handshake failed: {:?}", e),
+                            }
+                        });
+                    } else {
+                        tokio::spawn(async move { pool::handle_pool_client(s, board, worker_c).await });
+                    }
+                    continue;
+                }
+
+                let mode_c = mode_arc.clone();
+                let signer_c = signer.clone();
+                let worker_c = worker.clone();
+                match (secure, legacy_line) {
+                    (true, true) => {
+                        tokio::spawn(async move {
+                            match transport::server_handshake(s).await {
+                                Ok(secure_stream) => handle_secure_client(secure_stream, mode_c, signer_c, worker_c).await,
+                                Err(e) => eprintln!("Secure handshake failed: {:?}", e),
+                            }
+                        });
+                    }
+                    (true, false) => {
+                        tokio::spawn(async move {
+                            match transport::server_handshake(s).await {
+                                Ok(secure_stream) => {
+                                    handle_secure_client_binary(secure_stream, mode_c, signer_c, worker_c).await
+                                }
+                                Err(e) => eprintln!("Secure handshake failed: {:?}", e),
+                            }
+                        });
+                    }
+                    (false, true) => {
+                        tokio::spawn(async move { handle_client(s, mode_c, signer_c, worker_c).await });
+                    }
+                    (false, false) => {
+                        tokio::spawn(async move { handle_client_binary(s, mode_c, signer_c, worker_c).await });
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Listener error: {:?}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/// Compute AshMaize hash hex using ce-ashmaize crate (native implementation).
+/// 'rom_init_hex' is optional hex string (no_pre_mine) required by algorithm init.
+/// Return lowercase hex string of hash bytes.
+///
+/// Legacy text-protocol entry point: the "hex" rom_init string's raw ASCII
+/// bytes are used directly as the ROM seed (never actually hex-decoded),
+/// preserved here for backward compatibility with `--legacy-line` clients.
+/// Blocking/CPU-heavy; callers run this via [`WorkerPool::run`].
+fn native_hash_hex(pre: &str, rom_init_hex: Option<&str>) -> Result<String> {
+    let out = native_hash_bytes(pre.as_bytes(), rom_init_hex.map(str::as_bytes), 8, 256)?;
+    Ok(hex::encode(out))
+}
+
+/// Compute the AshMaize digest for `pre` directly from bytes, taking the
+/// ROM seed and the `rounds`/`out_len` parameters from the request instead
+/// of the old hard-coded `8, 256`. Blocking/CPU-heavy; callers run this via
+/// [`WorkerPool::run`] rather than calling it directly on an async task.
+pub(crate) fn native_hash_bytes(pre: &[u8], rom_init: Option<&[u8]>, rounds: u32, out_len: u32) -> Result<Vec<u8>> {
+    #[cfg(feature = "native_ashmaize")]
+    {
+        let key = rom_init.map(hex::encode).unwrap_or_else(|| "default".to_string());
+
+        const PRE_SIZE: u64 = 16 * 1024 * 1024; // 16MB
+        const MIXING_NUMBERS: u32 = 4;
+        const TOTAL_SIZE: u64 = 1024 * 1024 * 1024; // 1GB
+
+        // The cache hit path only ever needs the lock for the HashMap lookup
+        // itself. A miss falls through to generation/disk I/O (seconds to
+        // minutes, plus up to 1 GB of writes) that must run *without* holding
+        // the lock, or every other rom_init's hash requests would stall
+        // behind it -- exactly what the bounded worker pool exists to
+        // avoid.
+        let hit = rom_cache().lock().unwrap().get(&key).cloned();
+        let rom_arc = if let Some(r) = hit {
+            r
+        } else {
+            let seed = match rom_init {
+                Some(bytes) => {
+                    println!("[native_hash_bytes] Using RAW ROM init ({} bytes)", bytes.len());
+                    bytes.to_vec()
+                }
+                None => b"default_seed".to_vec(),
+            };
+
+            let cached = if let Some(dir) = rom_dir() {
+                match rom_cache::load(dir, &seed, PRE_SIZE, MIXING_NUMBERS, TOTAL_SIZE) {
+                    Ok(Some(mmap)) => {
+                        println!("[native_hash_bytes] Loaded ROM from --rom-dir cache (key {key})");
+                        CachedRom::Mapped(mmap)
+                    }
+                    Ok(None) => {
+                        let rom = generate_and_persist_rom(dir, &seed, PRE_SIZE, MIXING_NUMBERS, TOTAL_SIZE);
+                        CachedRom::Owned(rom)
+                    }
+                    Err(e) => {
+                        eprintln!("[native_hash_bytes] --rom-dir cache lookup failed, regenerating: {e:?}");
+                        let rom = generate_and_persist_rom(dir, &seed, PRE_SIZE, MIXING_NUMBERS, TOTAL_SIZE);
+                        CachedRom::Owned(rom)
+                    }
+                }
+            } else {
+                CachedRom::Owned(Rom::new(
+                    &seed,
+                    RomGenerationType::TwoStep { pre_size: PRE_SIZE as usize, mixing_numbers: MIXING_NUMBERS },
+                    TOTAL_SIZE as usize,
+                ))
+            };
+
+            let arc = std::sync::Arc::new(cached);
+            // Another connection may have generated/inserted the same key
+            // while we were generating without the lock held; prefer
+            // whichever landed first so we don't keep two redundant 1 GB
+            // ROMs alive for the same seed.
+            rom_cache().lock().unwrap().entry(key.clone()).or_insert_with(|| arc.clone()).clone()
+        };
+
+        let hash_bytes = rom_arc.hash(pre, rounds as usize, out_len as usize);
+        return Ok(hash_bytes);
+    }
+
+    #[cfg(not(feature = "native_ashmaize"))]
+    {
+        anyhow::bail!(
+            "Native AshMaize not enabled. Compile with --features native_ashmaize"
+        );
+    }
+}
+
+/// Generate a ROM and, best-effort, persist it under `dir` for the next
+/// process to pick up via [`rom_cache::load`]. A persistence failure is
+/// logged but doesn't fail the hash request, since the freshly generated
+/// ROM is still perfectly usable in memory for this process.
+#[cfg(feature = "native_ashmaize")]
+fn generate_and_persist_rom(dir: &std::path::Path, seed: &[u8], pre_size: u64, mixing_numbers: u32, total_size: u64) -> Rom {
+    println!("[native_hash_bytes] Generating ROM (not found in --rom-dir cache), this may take a while...");
+    let rom = Rom::new(
+        seed,
+        RomGenerationType::TwoStep { pre_size: pre_size as usize, mixing_numbers },
+        total_size as usize,
+    );
+    if let Err(e) = rom_cache::store(dir, seed, pre_size, mixing_numbers, total_size, rom.as_bytes()) {
+        eprintln!("[native_hash_bytes] Failed to persist ROM to --rom-dir: {e:?}");
+    }
+    rom
+}
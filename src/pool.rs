@@ -0,0 +1,472 @@
+//! Stratum-style job distribution and share validation for `DaemonMode::Pool`.
+//!
+//! A [`Job`] hands out a ROM seed, a preimage prefix, and a 256-bit
+//! target; the client appends a nonce and submits a [`Share`]. The server
+//! recomputes against the cached ROM for that job and accepts iff the
+//! digest, read as a big-endian integer, is `<= target`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWrite;
+use tokio::net::TcpStream;
+
+use crate::transport::SecureStream;
+use crate::worker_pool::WorkerPool;
+use crate::{native_hash_bytes, protocol};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub job_id: u64,
+    pub rom_init: Option<Vec<u8>>,
+    pub preimage_prefix: Vec<u8>,
+    pub target: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Share {
+    pub job_id: u64,
+    pub nonce: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum PoolMessage {
+    Job(Job),
+    Share(Share),
+    Accepted,
+    Rejected { reason: String },
+}
+
+/// How often a connection checks for a newer job between share submissions.
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How many jobs stay valid at once, so `job_id` lookups stay bounded and
+/// anything older is rejected as stale.
+const MAX_LIVE_JOBS: usize = 8;
+/// Largest `seen_nonces` set a single live job will track. Without a cap a
+/// client could submit an unbounded stream of garbage nonces against one
+/// job and grow its set for the whole ~`MAX_LIVE_JOBS` rotation window;
+/// once full, further never-seen nonces are rejected outright rather than
+/// evicted and re-admitted, since there's no way to tell a late-arriving
+/// legitimate nonce from more garbage.
+const MAX_NONCES_PER_JOB: usize = 10_000;
+
+/// Converts a leading-zero-bit difficulty into a 256-bit big-endian target
+/// threshold (`target = u256::MAX >> leading_zero_bits`).
+pub fn target_from_leading_zero_bits(leading_zero_bits: u32) -> [u8; 32] {
+    let bits = leading_zero_bits.min(256);
+    let mut target = [0xffu8; 32];
+    let full_bytes = (bits / 8) as usize;
+    for b in target.iter_mut().take(full_bytes) {
+        *b = 0;
+    }
+    let rem_bits = bits % 8;
+    if full_bytes < 32 && rem_bits > 0 {
+        target[full_bytes] = 0xffu8 >> rem_bits;
+    }
+    target
+}
+
+/// Resolves the target for a `--mode pool` flag given the target (if any)
+/// an earlier `--difficulty` already set for this run. Mirrors the
+/// `DaemonMode::Pool` merge rule in `main`'s argv loop: switching into pool
+/// mode must not clobber a target a preceding `--difficulty` established,
+/// the same way `--rom` preserves `rom_init` regardless of flag order.
+pub fn mode_pool_target(existing_target: Option<[u8; 32]>, default_bits: u32) -> [u8; 32] {
+    existing_target.unwrap_or_else(|| target_from_leading_zero_bits(default_bits))
+}
+
+/// Human-readable `difficulty` ratio: how many times harder than the
+/// easiest possible (all-0xff) target.
+pub fn difficulty_ratio(target: &[u8; 32]) -> f64 {
+    let max = big_endian_to_f64(&[0xffu8; 32]);
+    let cur = big_endian_to_f64(target);
+    if cur <= 0.0 { f64::INFINITY } else { max / cur }
+}
+
+fn big_endian_to_f64(bytes: &[u8; 32]) -> f64 {
+    bytes.iter().fold(0f64, |acc, &b| acc * 256.0 + b as f64)
+}
+
+/// `true` iff `hash`, interpreted as a big-endian integer, is `<= target`.
+/// A digest shorter than 32 bytes is zero-extended on the left; for one
+/// longer than 32 bytes only its last 32 bytes are compared and its leading
+/// bytes are ignored.
+pub fn hash_meets_target(hash: &[u8], target: &[u8; 32]) -> bool {
+    let mut padded = [0u8; 32];
+    let n = hash.len().min(32);
+    padded[32 - n..].copy_from_slice(&hash[hash.len() - n..]);
+    padded <= *target
+}
+
+/// Tracks the live job set plus accepted/rejected share counters for a pool.
+pub struct JobBoard {
+    next_id: AtomicU64,
+    current: Mutex<Option<Job>>,
+    live: Mutex<HashMap<u64, Job>>,
+    /// Nonces already submitted for each live job, so a client can't inflate
+    /// `accepted`/`rejected` by resubmitting the same [`Share`] repeatedly.
+    /// Pruned alongside `live` so it can't grow past [`MAX_LIVE_JOBS`] worth.
+    seen_nonces: Mutex<HashMap<u64, HashSet<Vec<u8>>>>,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl JobBoard {
+    pub fn new() -> Self {
+        JobBoard {
+            next_id: AtomicU64::new(1),
+            current: Mutex::new(None),
+            live: Mutex::new(HashMap::new()),
+            seen_nonces: Mutex::new(HashMap::new()),
+            accepted: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Mint and publish a new job, retiring the oldest once more than
+    /// [`MAX_LIVE_JOBS`] are outstanding so stale `job_id`s get rejected.
+    pub fn rotate(&self, rom_init: Option<Vec<u8>>, target: [u8; 32]) -> Job {
+        let job_id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let job = Job {
+            job_id,
+            rom_init,
+            preimage_prefix: format!("job-{}-", job_id).into_bytes(),
+            target,
+        };
+
+        *self.current.lock().unwrap() = Some(job.clone());
+        let mut live = self.live.lock().unwrap();
+        live.insert(job_id, job.clone());
+        self.seen_nonces.lock().unwrap().insert(job_id, HashSet::new());
+        if live.len() > MAX_LIVE_JOBS {
+            if let Some(&oldest) = live.keys().min() {
+                live.remove(&oldest);
+                self.seen_nonces.lock().unwrap().remove(&oldest);
+            }
+        }
+        job
+    }
+
+    pub fn current(&self) -> Option<Job> {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, job_id: u64) -> Option<Job> {
+        self.live.lock().unwrap().get(&job_id).cloned()
+    }
+
+    /// Records `nonce` as submitted for `job_id`. Returns `true` the first
+    /// time a given `(job_id, nonce)` pair is seen, `false` on a replay.
+    /// `false` for an unknown/evicted `job_id` too, so a replay of a share
+    /// for a job that's since rotated out can't be mistaken for fresh.
+    /// Also `false` once a job's set has already reached
+    /// [`MAX_NONCES_PER_JOB`], so a flood of never-seen nonces against one
+    /// job can't grow its set without bound.
+    pub fn mark_nonce_seen(&self, job_id: u64, nonce: &[u8]) -> bool {
+        match self.seen_nonces.lock().unwrap().get_mut(&job_id) {
+            Some(nonces) => {
+                if nonces.contains(nonce) {
+                    return false;
+                }
+                if nonces.len() >= MAX_NONCES_PER_JOB {
+                    return false;
+                }
+                nonces.insert(nonce.to_vec())
+            }
+            None => false,
+        }
+    }
+
+    pub fn record_accept(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reject(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> (u64, u64) {
+        (self.accepted.load(Ordering::Relaxed), self.rejected.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for JobBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Connection loop for `DaemonMode::Pool`: send the current job, then
+/// alternate between reading submitted shares and, on idle poll timeouts,
+/// pushing a newer job once [`JobBoard::rotate`] has published one.
+pub async fn handle_pool_client(mut stream: TcpStream, board: Arc<JobBoard>, worker: WorkerPool) {
+    let peer = stream.peer_addr().ok();
+
+    let mut last_sent_job_id = 0u64;
+    if let Some(job) = board.current() {
+        if send_job(&mut stream, &job).await.is_err() {
+            return;
+        }
+        last_sent_job_id = job.job_id;
+    }
+
+    loop {
+        match tokio::time::timeout(JOB_POLL_INTERVAL, protocol::read_frame(&mut stream)).await {
+            Ok(Ok(None)) => break,
+            Ok(Ok(Some(body))) => {
+                let msg: PoolMessage = match postcard::from_bytes(&body) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        eprintln!("Bad pool message from {:?}: {:?}", peer, e);
+                        break;
+                    }
+                };
+                if let PoolMessage::Share(share) = msg {
+                    let verdict = validate_share(&board, &worker, share).await;
+                    let reply = match &verdict {
+                        Ok(()) => {
+                            board.record_accept();
+                            PoolMessage::Accepted
+                        }
+                        Err(reason) => {
+                            board.record_reject();
+                            PoolMessage::Rejected { reason: reason.clone() }
+                        }
+                    };
+                    if send_message(&mut stream, &reply).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            Ok(Err(e)) => {
+                eprintln!("Pool read error from {:?}: {:?}", peer, e);
+                break;
+            }
+            Err(_timed_out) => {
+                if let Some(job) = board.current() {
+                    if job.job_id != last_sent_job_id {
+                        if send_job(&mut stream, &job).await.is_err() {
+                            break;
+                        }
+                        last_sent_job_id = job.job_id;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Same job/share loop as [`handle_pool_client`], but running over a
+/// `--secure` [`SecureStream`] instead of a plaintext `TcpStream`, so
+/// `--secure --mode pool` isn't silently served in the clear.
+pub async fn handle_secure_pool_client(mut secure: SecureStream, board: Arc<JobBoard>, worker: WorkerPool) {
+    let peer = secure.peer_addr().ok();
+
+    let mut last_sent_job_id = 0u64;
+    if let Some(job) = board.current() {
+        if send_job_secure(&mut secure, &job).await.is_err() {
+            return;
+        }
+        last_sent_job_id = job.job_id;
+    }
+
+    loop {
+        match tokio::time::timeout(JOB_POLL_INTERVAL, secure.read_frame()).await {
+            Ok(Ok(None)) => break,
+            Ok(Ok(Some(body))) => {
+                let msg: PoolMessage = match postcard::from_bytes(&body) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        eprintln!("Bad pool message from {:?}: {:?}", peer, e);
+                        break;
+                    }
+                };
+                if let PoolMessage::Share(share) = msg {
+                    let verdict = validate_share(&board, &worker, share).await;
+                    let reply = match &verdict {
+                        Ok(()) => {
+                            board.record_accept();
+                            PoolMessage::Accepted
+                        }
+                        Err(reason) => {
+                            board.record_reject();
+                            PoolMessage::Rejected { reason: reason.clone() }
+                        }
+                    };
+                    if send_message_secure(&mut secure, &reply).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            Ok(Err(e)) => {
+                eprintln!("Secure pool read error from {:?}: {:?}", peer, e);
+                break;
+            }
+            Err(_timed_out) => {
+                if let Some(job) = board.current() {
+                    if job.job_id != last_sent_job_id {
+                        if send_job_secure(&mut secure, &job).await.is_err() {
+                            break;
+                        }
+                        last_sent_job_id = job.job_id;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn validate_share(board: &JobBoard, worker: &WorkerPool, share: Share) -> Result<(), String> {
+    let job = board.get(share.job_id).ok_or_else(|| "unknown or stale job_id".to_string())?;
+
+    let mut preimage = job.preimage_prefix.clone();
+    preimage.extend_from_slice(&share.nonce);
+
+    // out_len=32 matches the 256-bit target width exactly, so
+    // hash_meets_target compares the whole digest instead of discarding most
+    // of a larger one; it's also cheaper since nothing past 32 bytes would
+    // ever be looked at.
+    let digest = worker
+        .run(move || native_hash_bytes(&preimage, job.rom_init.as_deref(), 8, 32))
+        .await
+        .map_err(|e| format!("hash worker failed: {e}"))?
+        .map_err(|e| format!("hash failed: {e}"))?;
+
+    // Only consume the (job_id, nonce) pair once we know the hash actually
+    // computed -- a worker/infra failure above must leave it retryable, or a
+    // legitimately-mined share could be burned by a transient error and then
+    // permanently rejected as a replay on resubmission.
+    if !board.mark_nonce_seen(job.job_id, &share.nonce) {
+        return Err("nonce already submitted for this job".to_string());
+    }
+
+    if hash_meets_target(&digest, &job.target) {
+        Ok(())
+    } else {
+        Err("digest does not meet target".to_string())
+    }
+}
+
+async fn send_job<W: AsyncWrite + Unpin>(w: &mut W, job: &Job) -> anyhow::Result<()> {
+    send_message(w, &PoolMessage::Job(job.clone())).await
+}
+
+async fn send_message<W: AsyncWrite + Unpin>(w: &mut W, msg: &PoolMessage) -> anyhow::Result<()> {
+    let body = postcard::to_allocvec(msg)?;
+    protocol::write_frame(w, &body).await
+}
+
+async fn send_job_secure(secure: &mut SecureStream, job: &Job) -> anyhow::Result<()> {
+    send_message_secure(secure, &PoolMessage::Job(job.clone())).await
+}
+
+async fn send_message_secure(secure: &mut SecureStream, msg: &PoolMessage) -> anyhow::Result<()> {
+    let body = postcard::to_allocvec(msg)?;
+    secure.write_frame(&body).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_from_leading_zero_bits_covers_whole_and_partial_bytes() {
+        assert_eq!(target_from_leading_zero_bits(0), [0xffu8; 32]);
+
+        let mut eight_bits = [0xffu8; 32];
+        eight_bits[0] = 0;
+        assert_eq!(target_from_leading_zero_bits(8), eight_bits);
+
+        // 4 leading zero bits within the first byte: top nibble cleared,
+        // bottom nibble still all-ones.
+        let mut four_bits = [0xffu8; 32];
+        four_bits[0] = 0x0f;
+        assert_eq!(target_from_leading_zero_bits(4), four_bits);
+
+        assert_eq!(target_from_leading_zero_bits(256), [0u8; 32]);
+        // Out-of-range bit counts clamp rather than panic or wrap.
+        assert_eq!(target_from_leading_zero_bits(1000), [0u8; 32]);
+    }
+
+    #[test]
+    fn hash_meets_target_compares_as_big_endian_integer() {
+        let target = target_from_leading_zero_bits(8); // top byte must be 0
+
+        let mut under = [0u8; 32];
+        under[1] = 1;
+        assert!(hash_meets_target(&under, &target));
+
+        let mut over = [0u8; 32];
+        over[0] = 1;
+        assert!(!hash_meets_target(&over, &target));
+
+        // Shorter digests are treated as right-aligned (big-endian, so
+        // missing leading bytes are implicitly zero).
+        assert!(hash_meets_target(&[0xff, 0xff], &target_from_leading_zero_bits(0)));
+    }
+
+    #[test]
+    fn mode_pool_target_preserves_an_earlier_difficulty_flag() {
+        const DEFAULT_BITS: u32 = 20;
+
+        // `--difficulty 24 --mode pool`: the target from --difficulty must
+        // survive the switch into pool mode instead of being reset to the
+        // default, regardless of which flag came first.
+        let earlier_difficulty = target_from_leading_zero_bits(24);
+        assert_eq!(mode_pool_target(Some(earlier_difficulty), DEFAULT_BITS), earlier_difficulty);
+
+        // `--mode pool` with no preceding --difficulty falls back to the default.
+        assert_eq!(mode_pool_target(None, DEFAULT_BITS), target_from_leading_zero_bits(DEFAULT_BITS));
+    }
+
+    #[test]
+    fn job_board_rotate_and_get_track_live_jobs() {
+        let board = JobBoard::new();
+        assert!(board.current().is_none());
+
+        let job = board.rotate(None, target_from_leading_zero_bits(0));
+        assert_eq!(board.current().unwrap().job_id, job.job_id);
+        assert_eq!(board.get(job.job_id).unwrap().job_id, job.job_id);
+        assert!(board.get(job.job_id + 1).is_none());
+    }
+
+    #[test]
+    fn job_board_evicts_oldest_job_past_max_live_jobs() {
+        let board = JobBoard::new();
+        let first = board.rotate(None, target_from_leading_zero_bits(0));
+        for _ in 0..MAX_LIVE_JOBS {
+            board.rotate(None, target_from_leading_zero_bits(0));
+        }
+        assert!(board.get(first.job_id).is_none());
+    }
+
+    #[test]
+    fn mark_nonce_seen_rejects_replays_and_unknown_jobs() {
+        let board = JobBoard::new();
+        let job = board.rotate(None, target_from_leading_zero_bits(0));
+
+        assert!(board.mark_nonce_seen(job.job_id, b"nonce-a"));
+        assert!(!board.mark_nonce_seen(job.job_id, b"nonce-a"));
+        assert!(board.mark_nonce_seen(job.job_id, b"nonce-b"));
+        assert!(!board.mark_nonce_seen(job.job_id + 1, b"nonce-a"));
+    }
+
+    #[test]
+    fn mark_nonce_seen_caps_growth_per_job() {
+        let board = JobBoard::new();
+        let job = board.rotate(None, target_from_leading_zero_bits(0));
+
+        for i in 0..MAX_NONCES_PER_JOB {
+            assert!(board.mark_nonce_seen(job.job_id, &(i as u64).to_be_bytes()));
+        }
+        // The set is now full; a never-seen nonce is rejected rather than
+        // growing the set further.
+        assert!(!board.mark_nonce_seen(job.job_id, b"one-too-many"));
+        // A replay of an already-recorded nonce is still correctly reported
+        // as a replay, not lumped in with the cap rejection.
+        assert!(!board.mark_nonce_seen(job.job_id, &0u64.to_be_bytes()));
+    }
+}
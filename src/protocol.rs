@@ -0,0 +1,173 @@
+//! Length-prefixed binary wire protocol (the default; `--legacy-line`
+//! falls back to the old `|`-delimited text format).
+//!
+//! Requests and responses are `postcard`-serialized and framed as a
+//! big-endian `u32` byte length followed by exactly that many bytes.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest frame body this daemon will read, plaintext or `--secure`. Bounds
+/// an untrusted peer's length prefix so it can't make us allocate gigabytes
+/// (or the ROM's full 1 GB) before a single byte of the frame has arrived.
+pub const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+/// Largest `rounds`/`out_len` a [`Request`] may ask for. Well above the old
+/// hard-coded `8, 256` defaults, but bounded so a client can't pin a worker
+/// permit indefinitely or force an oversized output allocation.
+pub const MAX_ROUNDS: u32 = 64;
+pub const MAX_OUT_LEN: u32 = 4096;
+
+#[derive(Serialize, Deserialize)]
+pub struct Request {
+    pub rom_init: Option<Vec<u8>>,
+    pub preimage: Vec<u8>,
+    pub out_len: u32,
+    pub rounds: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Response {
+    pub hash: Vec<u8>,
+    pub error: Option<String>,
+    /// `name:base64(signature)` from `NamedSigningKey::sign_response`, set
+    /// iff the daemon was started with `--sign-key` and the request
+    /// succeeded (`None` whenever `error` is set). Mirrors the trailing
+    /// field the line protocol appends to its response line.
+    pub signature: Option<String>,
+}
+
+/// Read one length-prefixed frame. Returns `None` on clean EOF.
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    if let Err(e) = r.read_exact(&mut len_buf).await {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            return Ok(None);
+        }
+        return Err(e.into());
+    }
+    let len = u32::from_be_bytes(len_buf);
+    if len > MAX_FRAME_LEN {
+        anyhow::bail!("frame of {len} bytes exceeds MAX_FRAME_LEN ({MAX_FRAME_LEN})");
+    }
+    let mut body = vec![0u8; len as usize];
+    r.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+/// Write `body` as one length-prefixed frame.
+pub async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, body: &[u8]) -> Result<()> {
+    w.write_all(&(body.len() as u32).to_be_bytes()).await?;
+    w.write_all(body).await?;
+    w.flush().await?;
+    Ok(())
+}
+
+/// Read and decode the next [`Request`] frame. Returns `None` on clean EOF.
+pub async fn read_request<R: AsyncRead + Unpin>(r: &mut R) -> Result<Option<Request>> {
+    match read_frame(r).await? {
+        Some(body) => Ok(Some(postcard::from_bytes(&body).context("decoding request frame")?)),
+        None => Ok(None),
+    }
+}
+
+/// Encode and write a [`Response`] frame.
+pub async fn write_response<W: AsyncWrite + Unpin>(w: &mut W, resp: &Response) -> Result<()> {
+    let body = postcard::to_allocvec(resp).context("encoding response frame")?;
+    write_frame(w, &body).await
+}
+
+/// Reject a [`Request`] whose `rounds`/`out_len` fall outside
+/// [`MAX_ROUNDS`]/[`MAX_OUT_LEN`], before it's ever handed to the heavy
+/// native hashing path.
+pub fn validate_request_params(req: &Request) -> Result<()> {
+    if req.rounds == 0 || req.rounds > MAX_ROUNDS {
+        anyhow::bail!("rounds {} out of range (1..={MAX_ROUNDS})", req.rounds);
+    }
+    if req.out_len == 0 || req.out_len > MAX_OUT_LEN {
+        anyhow::bail!("out_len {} out of range (1..={MAX_OUT_LEN})", req.out_len);
+    }
+    Ok(())
+}
+
+/// Decode a [`Request`] from an already-read frame body (used by the
+/// `--secure` transport, which supplies decrypted frame bytes directly).
+pub fn decode_request(body: &[u8]) -> Result<Request> {
+    postcard::from_bytes(body).context("decoding request frame")
+}
+
+/// Encode a [`Response`] to bytes (used by the `--secure` transport, which
+/// seals the frame itself).
+pub fn encode_response(resp: &Response) -> Result<Vec<u8>> {
+    postcard::to_allocvec(resp).context("encoding response frame")
+}
+
+/// Encode a [`Request`] to bytes (used by clients, e.g. the `ping` CLI,
+/// that send a request rather than handle one).
+pub fn encode_request(req: &Request) -> Result<Vec<u8>> {
+    postcard::to_allocvec(req).context("encoding request frame")
+}
+
+/// Decode a [`Response`] from an already-read frame body (used by clients,
+/// e.g. the `ping` CLI, that receive a response rather than produce one).
+pub fn decode_response(body: &[u8]) -> Result<Response> {
+    postcard::from_bytes(body).context("decoding response frame")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn read_frame_rejects_length_over_max_frame_len() {
+        let (mut tx, mut rx) = tokio::io::duplex(64);
+        tx.write_all(&(MAX_FRAME_LEN + 1).to_be_bytes()).await.unwrap();
+
+        let err = read_frame(&mut rx).await.unwrap_err();
+        assert!(err.to_string().contains("MAX_FRAME_LEN"));
+    }
+
+    #[tokio::test]
+    async fn read_frame_returns_none_on_clean_eof() {
+        let (tx, mut rx) = tokio::io::duplex(64);
+        drop(tx);
+
+        assert!(read_frame(&mut rx).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn validate_request_params_enforces_rounds_and_out_len_bounds() {
+        let req = |rounds, out_len| Request { rom_init: None, preimage: Vec::new(), out_len, rounds };
+
+        assert!(validate_request_params(&req(0, 256)).is_err());
+        assert!(validate_request_params(&req(MAX_ROUNDS + 1, 256)).is_err());
+        assert!(validate_request_params(&req(1, 0)).is_err());
+        assert!(validate_request_params(&req(1, MAX_OUT_LEN + 1)).is_err());
+
+        assert!(validate_request_params(&req(1, 1)).is_ok());
+        assert!(validate_request_params(&req(MAX_ROUNDS, MAX_OUT_LEN)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn request_response_round_trip_through_write_and_read() {
+        let req = Request { rom_init: Some(vec![1, 2, 3]), preimage: vec![4, 5, 6], out_len: 256, rounds: 8 };
+        let body = encode_request(&req).unwrap();
+
+        let (mut tx, mut rx) = tokio::io::duplex(1024);
+        write_frame(&mut tx, &body).await.unwrap();
+        let received = read_request(&mut rx).await.unwrap().unwrap();
+        assert_eq!(received.rom_init, req.rom_init);
+        assert_eq!(received.preimage, req.preimage);
+        assert_eq!(received.out_len, req.out_len);
+        assert_eq!(received.rounds, req.rounds);
+
+        let resp = Response { hash: vec![7, 8, 9], error: None, signature: Some("k1:c2ln".to_string()) };
+        write_response(&mut tx, &resp).await.unwrap();
+        let body = read_frame(&mut rx).await.unwrap().unwrap();
+        let decoded = decode_response(&body).unwrap();
+        assert_eq!(decoded.hash, resp.hash);
+        assert_eq!(decoded.error, resp.error);
+        assert_eq!(decoded.signature, resp.signature);
+    }
+}
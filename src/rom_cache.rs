@@ -0,0 +1,270 @@
+//! On-disk persistence for generated ROMs, keyed by a hash of seed +
+//! generation params, with a header (same params plus a checksum) so a
+//! later run can validate a cache hit before mmapping it instead of paying
+//! for another multi-minute `Rom::new`.
+//!
+//! Feature-gated along with the rest of the native hashing path.
+#![cfg(feature = "native_ashmaize")]
+
+use std::fs::File;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use hex;
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+
+const MAGIC: &[u8; 8] = b"ASHROM01";
+/// magic(8) + seed_hash(32) + pre_size(8) + mixing_numbers(4) + total_size(8) + checksum(32)
+const HEADER_LEN: usize = 8 + 32 + 8 + 4 + 8 + 32;
+
+struct RomParams<'a> {
+    seed: &'a [u8],
+    pre_size: u64,
+    mixing_numbers: u32,
+    total_size: u64,
+}
+
+/// A value unique across concurrent `store` calls in this process, for
+/// building a per-writer tmp file name. `process::id()` alone isn't enough
+/// since two tasks in the same daemon can race to fill the same cache miss.
+fn tmp_nonce() -> u128 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    ((nanos as u128) << 64) | COUNTER.fetch_add(1, Ordering::Relaxed) as u128
+}
+
+fn seed_hash(seed: &[u8]) -> [u8; 32] {
+    let mut h = Sha256::new();
+    h.update(seed);
+    h.finalize().into()
+}
+
+/// Filename for a given `(seed, generation params, total_size)` tuple. Hashed
+/// rather than using the raw hex seed so long/binary seeds don't blow past
+/// filesystem name limits.
+fn cache_path(dir: &Path, params: &RomParams) -> PathBuf {
+    let mut h = Sha256::new();
+    h.update(seed_hash(params.seed));
+    h.update(params.pre_size.to_be_bytes());
+    h.update(params.mixing_numbers.to_be_bytes());
+    h.update(params.total_size.to_be_bytes());
+    let key = hex::encode(h.finalize());
+    dir.join(format!("{key}.rom"))
+}
+
+fn build_header(params: &RomParams, checksum: [u8; 32]) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    let mut pos = 0;
+    header[pos..pos + 8].copy_from_slice(MAGIC);
+    pos += 8;
+    header[pos..pos + 32].copy_from_slice(&seed_hash(params.seed));
+    pos += 32;
+    header[pos..pos + 8].copy_from_slice(&params.pre_size.to_be_bytes());
+    pos += 8;
+    header[pos..pos + 4].copy_from_slice(&params.mixing_numbers.to_be_bytes());
+    pos += 4;
+    header[pos..pos + 8].copy_from_slice(&params.total_size.to_be_bytes());
+    pos += 8;
+    header[pos..pos + 32].copy_from_slice(&checksum);
+    header
+}
+
+/// Write `body` (the generated ROM's raw bytes) to `dir`, keyed by `params`,
+/// prefixed with a header recording the generation parameters and a
+/// checksum of `body`. Best-effort: a write failure is reported to the
+/// caller but should not stop the daemon from serving the in-memory ROM it
+/// just generated.
+pub fn store(dir: &Path, seed: &[u8], pre_size: u64, mixing_numbers: u32, total_size: u64, body: &[u8]) -> Result<()> {
+    std::fs::create_dir_all(dir).context("creating --rom-dir")?;
+    let params = RomParams { seed, pre_size, mixing_numbers, total_size };
+
+    let checksum: [u8; 32] = {
+        let mut h = Sha256::new();
+        h.update(body);
+        h.finalize().into()
+    };
+    let header = build_header(&params, checksum);
+
+    let path = cache_path(dir, &params);
+    // Unique per writer: two connections racing to fill the same cache miss
+    // for an identical (seed, params) key both generate and `store` the
+    // same `path`, and a shared tmp name would let one rename steal the
+    // other's file out from under it mid-write.
+    let tmp_path = path.with_extension(format!("rom.{}.{}.tmp", std::process::id(), tmp_nonce()));
+    let mut f = File::create(&tmp_path).with_context(|| format!("creating {}", tmp_path.display()))?;
+    f.write_all(&header)?;
+    f.write_all(body)?;
+    f.flush()?;
+    drop(f);
+    std::fs::rename(&tmp_path, &path).with_context(|| format!("renaming {} into place", path.display()))?;
+    Ok(())
+}
+
+/// Memory-map `dir`'s cache file for `params` read-only, validating the
+/// header's parameters and body checksum first. Returns `Ok(None)` on a
+/// clean miss (no file, truncated file, mismatched params, or bad
+/// checksum) so the caller falls back to regenerating; returns `Err` only
+/// for I/O errors unrelated to cache validity.
+pub fn load(dir: &Path, seed: &[u8], pre_size: u64, mixing_numbers: u32, total_size: u64) -> Result<Option<Mmap>> {
+    let params = RomParams { seed, pre_size, mixing_numbers, total_size };
+    let path = cache_path(dir, &params);
+
+    let f = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context("opening ROM cache file"),
+    };
+
+    let expected_len = HEADER_LEN as u64 + total_size;
+    let actual_len = f.metadata().context("statting ROM cache file")?.len();
+    if actual_len != expected_len {
+        eprintln!("ROM cache file {} is truncated (expected {expected_len} bytes, found {actual_len}); regenerating", path.display());
+        return Ok(None);
+    }
+
+    // SAFETY: the file is not expected to be concurrently truncated or
+    // rewritten by another process while mapped; `store` always writes to a
+    // temp file and renames it into place to avoid partial-write races.
+    let mmap = unsafe { Mmap::map(&f) }.context("mmapping ROM cache file")?;
+
+    if !validate_header(&mmap, &params) {
+        eprintln!("ROM cache file {} has a stale or corrupt header; regenerating", path.display());
+        return Ok(None);
+    }
+
+    let checksum: [u8; 32] = {
+        let mut h = Sha256::new();
+        h.update(&mmap[HEADER_LEN..]);
+        h.finalize().into()
+    };
+    if checksum[..] != mmap[HEADER_LEN - 32..HEADER_LEN] {
+        eprintln!("ROM cache file {} failed its checksum; regenerating", path.display());
+        return Ok(None);
+    }
+
+    Ok(Some(mmap))
+}
+
+fn validate_header(mmap: &Mmap, params: &RomParams) -> bool {
+    if mmap.len() < HEADER_LEN {
+        return false;
+    }
+    if mmap[0..8] != MAGIC[..] {
+        return false;
+    }
+    if mmap[8..40] != seed_hash(params.seed)[..] {
+        return false;
+    }
+    let pre_size = u64::from_be_bytes(mmap[40..48].try_into().unwrap());
+    let mixing_numbers = u32::from_be_bytes(mmap[48..52].try_into().unwrap());
+    let total_size = u64::from_be_bytes(mmap[52..60].try_into().unwrap());
+    pre_size == params.pre_size && mixing_numbers == params.mixing_numbers && total_size == params.total_size
+}
+
+/// Raw ROM bytes within a validated mapping, past the header.
+pub fn body(mmap: &Mmap) -> &[u8] {
+    &mmap[HEADER_LEN..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("ashdaemon-rom-cache-test-{label}-{nanos}"))
+    }
+
+    #[test]
+    fn store_then_load_round_trips() {
+        let dir = temp_dir("round-trip");
+        let seed = b"test-seed";
+        let body = vec![0xABu8; 128];
+
+        store(&dir, seed, 16, 4, body.len() as u64, &body).unwrap();
+        let mmap = load(&dir, seed, 16, 4, body.len() as u64).unwrap().expect("cache hit");
+        assert_eq!(super::body(&mmap), body.as_slice());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_misses_on_different_params() {
+        let dir = temp_dir("param-mismatch");
+        let seed = b"seed";
+        let body = vec![1u8; 64];
+        store(&dir, seed, 16, 4, body.len() as u64, &body).unwrap();
+
+        // Different mixing_numbers -> different cache_path -> clean miss.
+        assert!(load(&dir, seed, 16, 5, body.len() as u64).unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_regenerates_on_truncated_file() {
+        let dir = temp_dir("truncated");
+        let seed = b"seed";
+        let body = vec![2u8; 64];
+        store(&dir, seed, 16, 4, body.len() as u64, &body).unwrap();
+
+        let path = cache_path(&dir, &RomParams { seed, pre_size: 16, mixing_numbers: 4, total_size: body.len() as u64 });
+        let truncated = std::fs::read(&path).unwrap()[..HEADER_LEN + body.len() / 2].to_vec();
+        std::fs::write(&path, truncated).unwrap();
+
+        assert!(load(&dir, seed, 16, 4, body.len() as u64).unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_store_for_same_key_both_succeed() {
+        let dir = temp_dir("concurrent-store");
+        std::fs::create_dir_all(&dir).unwrap();
+        let seed = b"racing-seed";
+        let body = vec![7u8; 64];
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let dir = dir.clone();
+                let body = body.clone();
+                std::thread::spawn(move || store(&dir, seed, 16, 4, body.len() as u64, &body))
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap().unwrap();
+        }
+
+        let mmap = load(&dir, seed, 16, 4, body.len() as u64).unwrap().expect("cache hit");
+        assert_eq!(super::body(&mmap), body.as_slice());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_regenerates_on_corrupt_checksum() {
+        let dir = temp_dir("corrupt-checksum");
+        let seed = b"seed";
+        let body = vec![3u8; 64];
+        store(&dir, seed, 16, 4, body.len() as u64, &body).unwrap();
+
+        let path = cache_path(&dir, &RomParams { seed, pre_size: 16, mixing_numbers: 4, total_size: body.len() as u64 });
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF; // flip a body byte without touching length or header
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(load(&dir, seed, 16, 4, body.len() as u64).unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+//! Ed25519 response signing, so a client can verify a digest actually came
+//! from this daemon.
+//!
+//! The daemon loads (or generates and persists) a keypair via
+//! [`NamedSigningKey::load_or_generate`] and signs `len(preimage) ||
+//! preimage || hash`. A [`Keyring`] of `name:base64` public keys lets a
+//! verifier trust several keys at once, for rotation.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as B64;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+
+/// A signing keypair together with the short name it's published under.
+pub struct NamedSigningKey {
+    name: String,
+    signing_key: SigningKey,
+}
+
+impl NamedSigningKey {
+    /// Load a persisted key from `path`, generating and persisting a fresh
+    /// one if it doesn't exist yet. The key's name is the file's stem,
+    /// e.g. `./keys/daemon.key` publishes as `daemon`.
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("daemon")
+            .to_string();
+
+        if path.exists() {
+            let bytes = fs::read(path).with_context(|| format!("reading sign key {:?}", path))?;
+            let seed: [u8; 32] = bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("sign key file {:?} must be exactly 32 bytes", path))?;
+            Ok(NamedSigningKey { name, signing_key: SigningKey::from_bytes(&seed) })
+        } else {
+            let signing_key = SigningKey::generate(&mut OsRng);
+            if let Some(parent) = path.parent() {
+                if !parent.as_os_str().is_empty() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("creating {:?}", parent))?;
+                }
+            }
+            fs::write(path, signing_key.to_bytes())
+                .with_context(|| format!("persisting sign key {:?}", path))?;
+            Ok(NamedSigningKey { name, signing_key })
+        }
+    }
+
+    /// Compact `name:base64(pubkey)` form printed at startup and handed to
+    /// clients so they know which key to trust.
+    pub fn public_key_line(&self) -> String {
+        format!("{}:{}", self.name, B64.encode(self.signing_key.verifying_key().to_bytes()))
+    }
+
+    /// Sign `len(preimage) || preimage || hash` and render as
+    /// `name:base64(signature)`. The length prefix binds the preimage/hash
+    /// boundary so a signature can't be replayed against a relabeled split
+    /// of the same concatenated bytes.
+    pub fn sign_response(&self, preimage: &[u8], hash: &[u8]) -> String {
+        let sig: Signature = self.signing_key.sign(&signing_message(preimage, hash));
+        format!("{}:{}", self.name, B64.encode(sig.to_bytes()))
+    }
+}
+
+/// A set of trusted public keys keyed by name, built from `name:base64`
+/// entries (as printed by [`NamedSigningKey::public_key_line`]).
+#[derive(Default)]
+pub struct Keyring {
+    keys: HashMap<String, VerifyingKey>,
+}
+
+impl Keyring {
+    /// Parse a list of `name:base64(pubkey)` entries, e.g. what a rotating
+    /// daemon would publish across its old and current keys.
+    pub fn parse(entries: &[String]) -> Result<Self> {
+        let mut keys = HashMap::new();
+        for entry in entries {
+            let (name, b64) = entry
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("expected name:base64 key entry, got {:?}", entry))?;
+            let bytes = B64.decode(b64).with_context(|| format!("decoding key {:?}", name))?;
+            let arr: [u8; 32] = bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("key {:?} must be exactly 32 bytes", name))?;
+            let vk = VerifyingKey::from_bytes(&arr)
+                .with_context(|| format!("key {:?} is not a valid ed25519 public key", name))?;
+            keys.insert(name.to_string(), vk);
+        }
+        Ok(Keyring { keys })
+    }
+
+    /// Verify a `<hash_hex> <keyname>:<base64(signature)>` response line
+    /// against `preimage`. Fails if the key name is unknown or the
+    /// signature doesn't check out.
+    pub fn verify_response(&self, preimage: &[u8], response_line: &str) -> Result<()> {
+        let mut parts = response_line.trim().splitn(2, ' ');
+        let hash_hex = parts.next().filter(|s| !s.is_empty()).context("empty response line")?;
+        let sig_field = parts.next().context("response line is missing the signature field")?;
+
+        let (name, b64_sig) = sig_field
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("expected keyname:base64 signature, got {:?}", sig_field))?;
+        let vk = self
+            .keys
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("unknown signing key {:?}", name))?;
+
+        let hash = hex::decode(hash_hex).context("response hash is not valid hex")?;
+        let sig_bytes = B64.decode(b64_sig).context("signature is not valid base64")?;
+        let sig_arr: [u8; 64] = sig_bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("signature must be exactly 64 bytes"))?;
+        let sig = Signature::from_bytes(&sig_arr);
+
+        vk.verify(&signing_message(preimage, &hash), &sig)
+            .map_err(|e| anyhow::anyhow!("signature verification failed: {e}"))
+    }
+}
+
+/// Build the message actually signed: `len(preimage) as u64 LE || preimage
+/// || hash`. Without the length prefix, the bare concatenation leaves the
+/// preimage/hash boundary unauthenticated, so a signature for `(a, b)`
+/// would also verify for any other split `(a', b')` of the same bytes.
+fn signing_message(preimage: &[u8], hash: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(8 + preimage.len() + hash.len());
+    msg.extend_from_slice(&(preimage.len() as u64).to_le_bytes());
+    msg.extend_from_slice(preimage);
+    msg.extend_from_slice(hash);
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_key_path(label: &str) -> std::path::PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("ashdaemon-signing-test-{label}-{nanos}.key"))
+    }
+
+    #[test]
+    fn sign_then_verify_round_trips() {
+        let path = temp_key_path("sign-verify");
+        let key = NamedSigningKey::load_or_generate(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let preimage = b"some preimage bytes";
+        let hash = b"some hash bytes";
+        let line = format!("{} {}", hex::encode(hash), key.sign_response(preimage, hash));
+
+        let keyring = Keyring::parse(&[key.public_key_line()]).unwrap();
+        keyring.verify_response(preimage, &line).unwrap();
+    }
+
+    #[test]
+    fn verify_rejects_tampered_hash() {
+        let path = temp_key_path("tamper");
+        let key = NamedSigningKey::load_or_generate(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let preimage = b"preimage";
+        let hash = b"original hash";
+        let sig = key.sign_response(preimage, hash);
+        let tampered_line = format!("{} {}", hex::encode(b"different hash"), sig);
+
+        let keyring = Keyring::parse(&[key.public_key_line()]).unwrap();
+        assert!(keyring.verify_response(preimage, &tampered_line).is_err());
+    }
+
+    #[test]
+    fn verify_rejects_relabeled_preimage_hash_split() {
+        let path = temp_key_path("relabel");
+        let key = NamedSigningKey::load_or_generate(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let preimage = b"hello";
+        let hash = b"q-the-rest-of-the-hash";
+        let line = format!("{} {}", hex::encode(hash), key.sign_response(preimage, hash));
+
+        // Same concatenated bytes, split one byte later: "helloq" + "-the-rest-of-the-hash".
+        let shifted_preimage = b"helloq";
+        let shifted_hash = b"-the-rest-of-the-hash";
+        let shifted_line = format!("{} {}", hex::encode(shifted_hash), key.sign_response(preimage, hash));
+
+        let keyring = Keyring::parse(&[key.public_key_line()]).unwrap();
+        keyring.verify_response(preimage, &line).unwrap();
+        assert!(keyring.verify_response(shifted_preimage, &shifted_line).is_err());
+    }
+
+    #[test]
+    fn verify_rejects_unknown_key_name() {
+        let path = temp_key_path("unknown");
+        let key = NamedSigningKey::load_or_generate(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let preimage = b"preimage";
+        let hash = b"hash";
+        let line = format!("{} {}", hex::encode(hash), key.sign_response(preimage, hash));
+
+        let keyring = Keyring::parse(&[]).unwrap();
+        assert!(keyring.verify_response(preimage, &line).is_err());
+    }
+
+    #[test]
+    fn load_or_generate_persists_and_reloads_same_key() {
+        let path = temp_key_path("persist");
+        let first = NamedSigningKey::load_or_generate(&path).unwrap();
+        let second = NamedSigningKey::load_or_generate(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(first.public_key_line(), second.public_key_line());
+    }
+}
@@ -0,0 +1,103 @@
+//! Bounded dispatch for hash computations.
+//!
+//! Every hash call acquires a permit from a semaphore sized to `--workers`
+//! (default: available parallelism) before running on tokio's blocking
+//! thread pool, so a burst of connections waits for a permit instead of
+//! all spawning at once and thrashing memory on the 1 GB native ROM.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::Semaphore;
+
+/// Default worker count when `--workers` isn't given.
+pub fn default_workers() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+}
+
+#[derive(Clone)]
+pub struct WorkerPool {
+    permits: Arc<Semaphore>,
+}
+
+impl WorkerPool {
+    pub fn new(workers: usize) -> Self {
+        WorkerPool { permits: Arc::new(Semaphore::new(workers.max(1))) }
+    }
+
+    /// Run `f` on the blocking pool, holding one permit for the duration so
+    /// at most `workers` hash computations execute at once.
+    pub async fn run<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| anyhow::anyhow!("worker pool closed: {e}"))?;
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            f()
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("hash worker panicked: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn single_worker_serializes_concurrent_runs() {
+        let pool = WorkerPool::new(1);
+        let concurrent = Arc::new(AtomicUsize::new(0));
+        let max_concurrent = Arc::new(AtomicUsize::new(0));
+
+        let run = |pool: WorkerPool, concurrent: Arc<AtomicUsize>, max_concurrent: Arc<AtomicUsize>| {
+            pool.run(move || {
+                let now = concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+                max_concurrent.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(50));
+                concurrent.fetch_sub(1, Ordering::SeqCst);
+            })
+        };
+
+        let a = run(pool.clone(), concurrent.clone(), max_concurrent.clone());
+        let b = run(pool.clone(), concurrent.clone(), max_concurrent.clone());
+        let (ra, rb) = tokio::join!(a, b);
+        ra.unwrap();
+        rb.unwrap();
+
+        assert_eq!(max_concurrent.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn two_workers_allow_concurrent_runs() {
+        let pool = WorkerPool::new(2);
+        let concurrent = Arc::new(AtomicUsize::new(0));
+        let max_concurrent = Arc::new(AtomicUsize::new(0));
+
+        let run = |pool: WorkerPool, concurrent: Arc<AtomicUsize>, max_concurrent: Arc<AtomicUsize>| {
+            pool.run(move || {
+                let now = concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+                max_concurrent.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(50));
+                concurrent.fetch_sub(1, Ordering::SeqCst);
+            })
+        };
+
+        let a = run(pool.clone(), concurrent.clone(), max_concurrent.clone());
+        let b = run(pool.clone(), concurrent.clone(), max_concurrent.clone());
+        let (ra, rb) = tokio::join!(a, b);
+        ra.unwrap();
+        rb.unwrap();
+
+        assert_eq!(max_concurrent.load(Ordering::SeqCst), 2);
+    }
+}